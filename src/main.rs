@@ -6,26 +6,84 @@
 use freya::prelude::launch;
 use jsonschema;
 use serde_json::Value;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::BufReader;
+use std::path::{Component, Path};
 
 mod app;
 
 const SCHEMA_FILE_PATH: &str = "src/schema/config-schema.json";
 const CONFIG_FILE_PATH: &str = "src/config/config.json";
 
+/// What to do with a game whose save directories are missing on this machine
+enum MissingPaths {
+    Skip,
+    Create,
+    Error,
+}
+
+impl MissingPaths {
+    fn from_config(value: &Value) -> MissingPaths {
+        return match value.as_str() {
+            Some("create") => MissingPaths::Create,
+            Some("error") => MissingPaths::Error,
+            _ => MissingPaths::Skip,
+        };
+    }
+}
+
+/// What to do with a listed file that exists neither on PC nor on Deck
+enum MissingFiles {
+    Warn,
+    Error,
+    Ignore,
+}
+
+impl MissingFiles {
+    fn from_config(value: &Value) -> MissingFiles {
+        return match value.as_str() {
+            Some("error") => MissingFiles::Error,
+            Some("ignore") => MissingFiles::Ignore,
+            _ => MissingFiles::Warn,
+        };
+    }
+
+    fn reports(&self) -> bool {
+        return !matches!(self, MissingFiles::Ignore);
+    }
+
+    fn fails(&self, missing: &[String]) -> bool {
+        return matches!(self, MissingFiles::Error) && !missing.is_empty();
+    }
+}
+
+/// What happened to a single game during a sync run
+#[derive(Debug, PartialEq)]
+enum SyncOutcome {
+    Synced,
+    Skipped,
+    Failed,
+}
+
 struct DeckSaveButler {
     auto_sync: bool,
+    missing_paths: MissingPaths,
+    missing_files: MissingFiles,
     games: Vec<Value>,
 }
 
 impl DeckSaveButler {
     pub fn init() -> DeckSaveButler {
-        let (auto_sync, games) = Self::get_config();
-        return DeckSaveButler { auto_sync, games };
+        let (auto_sync, missing_paths, missing_files, games) = Self::get_config();
+        return DeckSaveButler {
+            auto_sync,
+            missing_paths,
+            missing_files,
+            games,
+        };
     }
 
-    fn get_config() -> (bool, Vec<Value>) {
+    fn get_config() -> (bool, MissingPaths, MissingFiles, Vec<Value>) {
         let schema_file =
             File::open(SCHEMA_FILE_PATH).expect("Should have been able to read the schema file");
         let schema_reader = BufReader::new(schema_file);
@@ -39,9 +97,11 @@ impl DeckSaveButler {
         assert!(jsonschema::is_valid(&schema, &config));
 
         let auto_sync = config["autoSync"].as_bool().unwrap();
+        let missing_paths = MissingPaths::from_config(&config["missingPaths"]);
+        let missing_files = MissingFiles::from_config(&config["missingFiles"]);
         let games = config["games"].as_array().unwrap().to_vec();
 
-        return (auto_sync, games);
+        return (auto_sync, missing_paths, missing_files, games);
     }
 
     pub fn sync_games(&self) -> bool {
@@ -49,12 +109,118 @@ impl DeckSaveButler {
 
         for game in &self.games {
             println!("{}", game["_id"]);
-            result &= self.sync_game(game["_id"].as_u64().unwrap())
+            result &= self.sync_entry(game) != SyncOutcome::Failed;
         }
 
         return result;
     }
 
+    fn sync_entry(&self, game: &Value) -> SyncOutcome {
+        let name = game["name"].as_str().unwrap();
+        let mut missing = Vec::new();
+
+        for key in ["pcPath", "deckPath"] {
+            let path = game[key].as_str().unwrap();
+
+            if path.is_empty() {
+                eprintln!("{}: {} is empty", name, key);
+                return SyncOutcome::Failed;
+            }
+
+            match fs::metadata(path) {
+                Ok(metadata) if !metadata.is_dir() => {
+                    eprintln!("{}: {} is not a directory", name, path);
+                    return SyncOutcome::Failed;
+                }
+                Ok(_) => {}
+                Err(_) => missing.push(path),
+            }
+        }
+
+        if !missing.is_empty() {
+            match self.missing_paths {
+                MissingPaths::Skip => {
+                    println!("{}: not present on this machine, skipping", name);
+                    return SyncOutcome::Skipped;
+                }
+                MissingPaths::Create => {
+                    if !Self::create_paths(name, &missing) {
+                        return SyncOutcome::Failed;
+                    }
+                }
+                MissingPaths::Error => {
+                    eprintln!("{}: {} not found", name, missing.join(", "));
+                    return SyncOutcome::Failed;
+                }
+            }
+        }
+
+        if !self.check_files(game) {
+            return SyncOutcome::Failed;
+        }
+
+        return match self.sync_game(game["_id"].as_u64().unwrap()) {
+            true => SyncOutcome::Synced,
+            false => SyncOutcome::Failed,
+        };
+    }
+
+    fn create_paths(name: &str, paths: &[&str]) -> bool {
+        for path in paths {
+            if let Err(error) = fs::create_dir_all(path) {
+                eprintln!("{}: could not create {}: {}", name, path, error);
+                return false;
+            }
+        }
+
+        return true;
+    }
+
+    /// Applies the missing files policy, returns false if the game should fail
+    fn check_files(&self, game: &Value) -> bool {
+        let name = game["name"].as_str().unwrap();
+        let missing = Self::find_missing_files(game);
+        let fails = self.missing_files.fails(&missing);
+
+        if self.missing_files.reports() {
+            for file in &missing {
+                let message = format!("{}: couldn't find {} in the PC or Deck saves", name, file);
+                match fails {
+                    true => eprintln!("{}", message),
+                    false => println!("{}", message),
+                }
+            }
+        }
+
+        return !fails;
+    }
+
+    /// Lists the files entries found on neither side, entries that would leave
+    /// the save directories (absolute or containing `..`) count as missing
+    fn find_missing_files(game: &Value) -> Vec<String> {
+        let files = match game["files"].as_array() {
+            Some(files) => files,
+            None => return Vec::new(),
+        };
+
+        let pc_path = Path::new(game["pcPath"].as_str().unwrap());
+        let deck_path = Path::new(game["deckPath"].as_str().unwrap());
+        let mut missing = Vec::new();
+
+        for file in files {
+            let file = file.as_str().unwrap();
+            let inside = Path::new(file)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+            if !inside || !(pc_path.join(file).exists() || deck_path.join(file).exists()) {
+                missing.push(file.to_string());
+            }
+        }
+
+        return missing;
+    }
+
     fn sync_game(&self, _id: u64) -> bool {
         return true;
     }
@@ -65,8 +231,210 @@ fn main() {
     let butler = DeckSaveButler::init();
     
     if butler.auto_sync {
-        butler.sync_games();
+        if !butler.sync_games() {
+            std::process::exit(1);
+        }
     } else {
         launch(app::app); // Be aware that this will block the thread
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    /// Scratch directory removed when the test ends
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!(
+                "deck-save-sync-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            return TempDir(dir);
+        }
+
+        fn join(&self, path: &str) -> PathBuf {
+            return self.0.join(path);
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn butler(
+        missing_paths: MissingPaths,
+        missing_files: MissingFiles,
+        games: Vec<Value>,
+    ) -> DeckSaveButler {
+        return DeckSaveButler {
+            auto_sync: true,
+            missing_paths,
+            missing_files,
+            games,
+        };
+    }
+
+    fn game(pc_path: &Path, deck_path: &Path) -> Value {
+        return json!({
+            "_id": 0,
+            "name": "Dark Souls III",
+            "pcPath": pc_path.to_str().unwrap(),
+            "deckPath": deck_path.to_str().unwrap(),
+            "files": null
+        });
+    }
+
+    /// A game that fails on its files check if it gets past the paths check
+    fn strict_game(dir: &TempDir) -> Value {
+        let mut game = game(&dir.join("pc"), &dir.join("deck"));
+        game["files"] = json!(["x.sav"]);
+        return game;
+    }
+
+    #[test]
+    fn skip_missing_pc_path() {
+        let dir = TempDir::new("skip-pc");
+        fs::create_dir(dir.join("deck")).unwrap();
+        let butler = butler(MissingPaths::Skip, MissingFiles::Error, vec![]);
+
+        assert_eq!(butler.sync_entry(&strict_game(&dir)), SyncOutcome::Skipped);
+        assert!(!dir.join("pc").exists());
+    }
+
+    #[test]
+    fn skip_missing_deck_path() {
+        let dir = TempDir::new("skip-deck");
+        fs::create_dir(dir.join("pc")).unwrap();
+        let butler = butler(MissingPaths::Skip, MissingFiles::Error, vec![]);
+
+        assert_eq!(butler.sync_entry(&strict_game(&dir)), SyncOutcome::Skipped);
+        assert!(!dir.join("deck").exists());
+    }
+
+    #[test]
+    fn error_on_missing_path() {
+        let dir = TempDir::new("error");
+        fs::create_dir(dir.join("pc")).unwrap();
+        let games = vec![game(&dir.join("pc"), &dir.join("deck"))];
+        let butler = butler(MissingPaths::Error, MissingFiles::Warn, games);
+
+        assert_eq!(butler.sync_entry(&butler.games[0]), SyncOutcome::Failed);
+        assert!(!butler.sync_games());
+        assert!(!dir.join("deck").exists());
+    }
+
+    #[test]
+    fn create_missing_paths() {
+        let dir = TempDir::new("create");
+        let games = vec![game(&dir.join("pc"), &dir.join("deck"))];
+        let butler = butler(MissingPaths::Create, MissingFiles::Warn, games);
+
+        assert_eq!(butler.sync_entry(&butler.games[0]), SyncOutcome::Synced);
+        assert!(dir.join("pc").is_dir());
+        assert!(dir.join("deck").is_dir());
+    }
+
+    #[test]
+    fn fail_on_path_that_is_a_file() {
+        let dir = TempDir::new("not-dir");
+        fs::write(dir.join("pc"), "").unwrap();
+        let butler = butler(MissingPaths::Create, MissingFiles::Warn, vec![]);
+        let game = game(&dir.join("pc"), &dir.join("deck"));
+
+        assert_eq!(butler.sync_entry(&game), SyncOutcome::Failed);
+        assert!(dir.join("pc").is_file());
+    }
+
+    #[test]
+    fn fail_on_empty_path() {
+        let dir = TempDir::new("empty");
+        let butler = butler(MissingPaths::Create, MissingFiles::Warn, vec![]);
+        let game = game(Path::new(""), &dir.join("deck"));
+
+        assert_eq!(butler.sync_entry(&game), SyncOutcome::Failed);
+    }
+
+    fn game_with_missing_file(name: &str) -> (TempDir, Value) {
+        let dir = TempDir::new(name);
+        fs::create_dir(dir.join("pc")).unwrap();
+        fs::create_dir(dir.join("deck")).unwrap();
+        fs::write(dir.join("pc").join("found.sav"), "").unwrap();
+
+        let mut game = game(&dir.join("pc"), &dir.join("deck"));
+        game["files"] = json!(["found.sav", "missing.sav"]);
+        return (dir, game);
+    }
+
+    #[test]
+    fn find_missing_files() {
+        let (_dir, game) = game_with_missing_file("files-find");
+
+        assert_eq!(DeckSaveButler::find_missing_files(&game), ["missing.sav"]);
+    }
+
+    #[test]
+    fn files_outside_save_paths_are_missing() {
+        let (dir, mut game) = game_with_missing_file("files-outside");
+        fs::write(dir.join("outside.sav"), "").unwrap();
+        let absolute = dir.join("outside.sav").to_str().unwrap().to_string();
+        game["files"] = json!([absolute, "../outside.sav", "./found.sav"]);
+
+        assert_eq!(
+            DeckSaveButler::find_missing_files(&game),
+            [absolute.as_str(), "../outside.sav"]
+        );
+    }
+
+    #[test]
+    fn warn_on_missing_file() {
+        let (_dir, game) = game_with_missing_file("files-warn");
+        let butler = butler(MissingPaths::Skip, MissingFiles::Warn, vec![game]);
+
+        assert!(butler.missing_files.reports());
+        assert!(!butler.missing_files.fails(&["missing.sav".to_string()]));
+        assert!(butler.check_files(&butler.games[0]));
+    }
+
+    #[test]
+    fn ignore_missing_file() {
+        let (_dir, game) = game_with_missing_file("files-ignore");
+        let butler = butler(MissingPaths::Skip, MissingFiles::Ignore, vec![game]);
+
+        assert!(!butler.missing_files.reports());
+        assert!(!butler.missing_files.fails(&["missing.sav".to_string()]));
+        assert!(butler.check_files(&butler.games[0]));
+    }
+
+    #[test]
+    fn error_on_missing_file() {
+        let (_dir, game) = game_with_missing_file("files-error");
+        let butler = butler(MissingPaths::Skip, MissingFiles::Error, vec![game]);
+
+        assert!(butler.missing_files.reports());
+        assert!(butler.missing_files.fails(&["missing.sav".to_string()]));
+        assert!(!butler.check_files(&butler.games[0]));
+        assert_eq!(butler.sync_entry(&butler.games[0]), SyncOutcome::Failed);
+        assert!(!butler.sync_games());
+    }
+
+    #[test]
+    fn null_files_are_not_checked() {
+        let (_dir, mut game) = game_with_missing_file("files-null");
+        game["files"] = Value::Null;
+        let butler = butler(MissingPaths::Skip, MissingFiles::Error, vec![game]);
+
+        assert!(DeckSaveButler::find_missing_files(&butler.games[0]).is_empty());
+        assert!(butler.check_files(&butler.games[0]));
+        assert!(butler.sync_games());
+    }
+}